@@ -25,6 +25,12 @@ error_chain! {
 			description("internal error"),
 			display("Internal Error"),
 		}
+
+		/// `state_unsubscribeStorage` was called with an id that isn't (or is no longer) live.
+		InvalidSubscriptionId {
+			description("invalid subscription id"),
+			display("Invalid or expired storage subscription id"),
+		}
 	}
 }
 
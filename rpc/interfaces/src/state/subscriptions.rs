@@ -0,0 +1,79 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! `state_subscribeStorage`/`state_unsubscribeStorage` support.
+//!
+//! `client::notifications::StorageNotifications` is the registry every storage change
+//! actually lands in; this bridges it to the RPC layer by handing callers a plain
+//! numeric id instead of the registry's own `SubscriberId`, so `unsubscribe_storage`
+//! can fail with `InvalidSubscriptionId` for an id the RPC client made up or already
+//! cancelled, rather than silently no-op'ing.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use client::notifications::{Filter, StorageChangeSet, StorageNotifications, SubscriberId};
+use futures::sync::mpsc::UnboundedReceiver;
+use primitives::H256;
+
+use error::{self, ErrorKind};
+
+/// An RPC-facing subscription id, handed out by `subscribe_storage` and later passed
+/// back into `unsubscribe_storage`.
+pub type RpcSubscriptionId = u64;
+
+/// Bridges `state_subscribeStorage`/`state_unsubscribeStorage` to a `StorageNotifications`
+/// registry, translating the RPC-facing id into the registry's own `SubscriberId`.
+pub struct StorageSubscriptions {
+	notifications: Arc<StorageNotifications>,
+	next_id: Mutex<RpcSubscriptionId>,
+	ids: Mutex<HashMap<RpcSubscriptionId, SubscriberId>>,
+}
+
+impl StorageSubscriptions {
+	/// Create a new bridge over `notifications`.
+	pub fn new(notifications: Arc<StorageNotifications>) -> Self {
+		StorageSubscriptions {
+			notifications,
+			next_id: Mutex::new(0),
+			ids: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Subscribe to storage changes under any of `keys`, or every change if `keys` is
+	/// empty. Returns the id `unsubscribe_storage` needs and the stream of changes.
+	pub fn subscribe_storage(&self, keys: Vec<Vec<u8>>) -> (RpcSubscriptionId, UnboundedReceiver<(H256, StorageChangeSet)>) {
+		let filter = if keys.is_empty() { Filter::All } else { Filter::Keys(keys) };
+		let (subscriber_id, stream) = self.notifications.subscribe(filter);
+
+		let mut next_id = self.next_id.lock().expect("only panics if a previous holder panicked");
+		let rpc_id = *next_id;
+		*next_id += 1;
+		self.ids.lock().expect("only panics if a previous holder panicked").insert(rpc_id, subscriber_id);
+
+		(rpc_id, stream)
+	}
+
+	/// Cancel a subscription. Fails with `InvalidSubscriptionId` if `rpc_id` is unknown,
+	/// i.e. was never handed out by `subscribe_storage` or has already been cancelled.
+	pub fn unsubscribe_storage(&self, rpc_id: RpcSubscriptionId) -> error::Result<()> {
+		let subscriber_id = self.ids.lock().expect("only panics if a previous holder panicked")
+			.remove(&rpc_id)
+			.ok_or(ErrorKind::InvalidSubscriptionId)?;
+		self.notifications.unsubscribe(subscriber_id);
+		Ok(())
+	}
+}
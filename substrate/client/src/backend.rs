@@ -18,6 +18,8 @@
 
 use state_machine;
 use error;
+use notifications::StorageNotifications;
+use primitives::H256;
 use primitives::block::{self, Id as BlockId};
 use primitives;
 
@@ -34,6 +36,11 @@ pub trait BlockImportOperation {
 	fn set_storage<I: Iterator<Item=(Vec<u8>, Option<Vec<u8>>)>>(&mut self, changes: I) -> error::Result<()>;
 	/// Inject storage data into the database replacing any existing data.
 	fn reset_storage<I: Iterator<Item=(Vec<u8>, Vec<u8>)>>(&mut self, iter: I) -> error::Result<()>;
+	/// Record the root of a just-completed Canonical Hash Trie interval.
+	///
+	/// Called by the backend itself (see `cht::is_final_block_of_cht`) while preparing the
+	/// operation that imports the interval's last block; never by callers of `begin_operation`.
+	fn set_cht_root(&mut self, cht_number: u64, cht_root: H256) -> error::Result<()>;
 }
 
 /// Client backend. Manages the data layer.
@@ -48,11 +55,21 @@ pub trait Backend: Send + Sync {
 	/// Begin a new block insertion transaction with given parent block id.
 	fn begin_operation(&self, block: BlockId) -> error::Result<Self::BlockImportOperation>;
 	/// Commit block insertion.
+	///
+	/// On success, must feed the transaction's storage changes to `storage_notifications`
+	/// under the newly-imported block's hash, so that `state_subscribeStorage`-style
+	/// subscribers find out about them without polling.
 	fn commit_operation(&self, transaction: Self::BlockImportOperation) -> error::Result<()>;
 	/// Returns reference to blockchain backend.
 	fn blockchain(&self) -> &Self::Blockchain;
 	/// Returns state backend for specified block.
 	fn state_at(&self, block: BlockId) -> error::Result<Self::State>;
+	/// Returns the stored CHT root for the interval `cht_number`, if that interval has
+	/// finalized and its root was recorded, so callers can check a CHT proof against it
+	/// without holding the full header chain.
+	fn cht_root(&self, cht_number: u64) -> error::Result<Option<H256>>;
+	/// Returns the registry of storage-change subscribers that `commit_operation` feeds.
+	fn storage_notifications(&self) -> &StorageNotifications;
 }
 
 /// Mark for all Backend implementations, that are making use of state data, stored locally.
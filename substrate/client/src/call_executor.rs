@@ -15,14 +15,17 @@
 // along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::sync::Arc;
+use futures::Future;
+use primitives::H256;
 use primitives::block::Id as BlockId;
 use state_machine::{self, OverlayedChanges, Backend as StateBackend, CodeExecutor};
-use state_machine::backend::InMemory as InMemoryStateBackend;
-use triehash::trie_root;
+use state_machine::proving_backend::check_execution_proof;
 
 use backend;
 use blockchain::Backend as ChainBackend;
+use cht;
 use error;
+use light;
 use light::Fetcher;
 
 /// Information regarding the result of a call.
@@ -48,6 +51,14 @@ pub trait CallExecutor {
 	///
 	/// No changes are made.
 	fn call_at_state<S: state_machine::Backend>(&self, state: &S, overlay: &mut OverlayedChanges, method: &str, call_data: &[u8]) -> Result<Vec<u8>, error::Error>;
+
+	/// Execute a call to a contract on top of state in a block of given hash, recording
+	/// the trie nodes touched along the way.
+	///
+	/// Returns both the call's result and the minimal set of trie nodes an independent
+	/// verifier needs to replay the call against the block's `state_root` and reach the
+	/// same result, without holding the rest of the state.
+	fn execution_proof(&self, id: &BlockId, method: &str, call_data: &[u8]) -> Result<(Vec<u8>, Vec<Vec<u8>>), error::Error>;
 }
 
 /// Call executor that executes methods locally, querying all required
@@ -86,6 +97,7 @@ impl<B, E> CallExecutor for LocalCallExecutor<B, E>
 		B: backend::LocalBackend,
 		E: CodeExecutor,
 		error::Error: From<<<B as backend::Backend>::State as StateBackend>::Error>,
+		<B as backend::Backend>::State: state_machine::proving_backend::TrieBackend,
 {
 	type Error = E::Error;
 
@@ -104,6 +116,32 @@ impl<B, E> CallExecutor for LocalCallExecutor<B, E>
 			call_data,
 		).map_err(Into::into)
 	}
+
+	// The server-side counterpart to `RemoteCallExecutor::call`'s proof check: runs the
+	// call through a `ProvingBackend` so the returned proof only ever covers the keys
+	// this particular call reads, rather than the whole state.
+	fn execution_proof(&self, id: &BlockId, method: &str, call_data: &[u8]) -> error::Result<(Vec<u8>, Vec<Vec<u8>>)> {
+		let trie_state = self.backend.state_at(*id)?;
+		let proving_backend = state_machine::proving_backend::ProvingBackend::new(&trie_state);
+		let mut changes = OverlayedChanges::default();
+		let return_data = state_machine::execute(
+			&proving_backend,
+			&mut changes,
+			&self.executor,
+			method,
+			call_data,
+		).map_err(Into::into)?;
+
+		// A call that enumerates the full state (e.g. one that reads `storage_root()`)
+		// can't be proven this way: `ProvingBackend::pairs()` only ever records the
+		// individual keys touched, never the whole state, so the proof below would
+		// silently be missing almost everything it needs to reproduce that call.
+		if proving_backend.enumerated_full_state() {
+			return Err(error::ErrorKind::NotAvailableOnLightClient.into());
+		}
+
+		Ok((return_data, proving_backend.extract_proof()))
+	}
 }
 
 impl<B, E> RemoteCallExecutor<B, E> {
@@ -122,70 +160,52 @@ impl<B, E> CallExecutor for RemoteCallExecutor<B, E>
 	type Error = E::Error;
 
 	fn call(&self, id: &BlockId, method: &str, call_data: &[u8]) -> error::Result<CallResult> {
-		let block_hash = match *id {
-			BlockId::Hash(hash) => hash,
-			BlockId::Number(number) => self.backend.blockchain().hash(number)?
-				.ok_or_else(|| error::ErrorKind::UnknownBlock(BlockId::Number(number)))?,
+		let (block_hash, block_number) = match *id {
+			BlockId::Hash(hash) => (hash, None),
+			BlockId::Number(number) => {
+				let hash = self.backend.blockchain().hash(number)?
+					.ok_or_else(|| error::ErrorKind::UnknownBlock(BlockId::Number(number)))?;
+				(hash, Some(number))
+			},
 		};
 
-		let (remote_result, remote_proof) = self.fetcher.execution_proof(block_hash, method, call_data)?;
-
-		// code below will be replaced with proper proof check once trie-based proofs will be possible
-
-		let remote_state = state_from_execution_proof(remote_proof);
-		let remote_state_root = trie_root(remote_state.pairs().into_iter()).0;
-
-		let local_header = self.backend.blockchain().header(BlockId::Hash(block_hash))?;
-		let local_header = local_header.ok_or_else(|| error::ErrorKind::UnknownBlock(BlockId::Hash(block_hash)))?;
-		let local_state_root = local_header.state_root;
-
-		if remote_state_root != *local_state_root {
-			return Err(error::ErrorKind::InvalidExecutionProof.into());
-		}
-
-		let mut changes = OverlayedChanges::default();
-		let local_result = state_machine::execute(
-			&remote_state,
-			&mut changes,
-			&self.executor,
-			method,
-			call_data,
-		)?;
+		let request = light::RemoteCallRequest { block: block_hash, method: method.into(), call_data: call_data.to_vec() };
+		let (remote_result, remote_proof) = self.fetcher.remote_call(request).wait()
+			.map_err(|_| error::Error::from(error::ErrorKind::RemoteFetchFailed))??;
+
+		// A light client only holds the handful of CHT roots it has already checked,
+		// not the full header chain, so the header backing `local_state_root` has to
+		// be fetched and checked against one of them - there's no "just trust whatever
+		// `blockchain()` happens to have cached" path that doesn't reopen the exact
+		// hole this request exists to close. That check needs a block *number* to pick
+		// the right CHT interval, which a bare `BlockId::Hash` doesn't carry; refuse
+		// those rather than fall back to an unverified header lookup.
+		let number = block_number.ok_or_else(|| error::Error::from(error::ErrorKind::NotAvailableOnLightClient))?;
+		let cht_number = cht::block_to_cht_number(number);
+		let cht_root = self.backend.cht_root(cht_number)?
+			.ok_or_else(|| error::Error::from(error::ErrorKind::UnknownBlock(BlockId::Number(number))))?;
+		let local_header = light::check_remote_header(&*self.fetcher, cht_root, light::RemoteHeaderRequest { cht_number, block: number })?;
+		let local_state_root: H256 = *local_header.state_root;
+
+		// Replaying the call against just the proof nodes, rooted at the header's
+		// `state_root` we already trust, stands in for transferring (and re-hashing)
+		// the whole remote state: any node the call touches that isn't in `remote_proof`
+		// surfaces as an error here rather than as a silently wrong `local_result`.
+		let (local_result, local_changes) = check_execution_proof(local_state_root, remote_proof, &self.executor, method, call_data)
+			.map_err(|_| error::Error::from(error::ErrorKind::InvalidExecutionProof))?;
 
 		if local_result != remote_result {
 			return Err(error::ErrorKind::InvalidExecutionProof.into());
 		}
 
-		Ok(CallResult { return_data: local_result, changes })
+		Ok(CallResult { return_data: local_result, changes: local_changes })
 	}
 
 	fn call_at_state<S: state_machine::Backend>(&self, _state: &S, _changes: &mut OverlayedChanges, _method: &str, _call_data: &[u8]) -> error::Result<Vec<u8>> {
 		Err(error::ErrorKind::NotAvailableOnLightClient.into())
 	}
-}
-
-/// Convert state to execution proof. Proof is simple the whole state (temporary).
-// TODO [light]: this method must be removed after trie-based proofs are landed.
-pub fn state_to_execution_proof<B: state_machine::Backend>(state: &B) -> Vec<Vec<u8>> {
-	state.pairs().into_iter()
-		.flat_map(|(k, v)| ::std::iter::once(k).chain(::std::iter::once(v)))
-		.collect()
-}
 
-/// Convert execution proof to in-memory state for check. Reverse function for state_to_execution_proof.
-// TODO [light]: this method must be removed after trie-based proofs are landed.
-fn state_from_execution_proof(proof: Vec<Vec<u8>>) -> InMemoryStateBackend {
-	let mut state = InMemoryStateBackend::new();
-	let mut proof_iter = proof.into_iter();
-	loop {
-		let key = proof_iter.next();
-		let value = proof_iter.next();
-		if let (Some(key), Some(value)) = (key, value) {
-			state.insert(key, value);
-		} else {
-			break;
-		}
+	fn execution_proof(&self, _id: &BlockId, _method: &str, _call_data: &[u8]) -> error::Result<(Vec<u8>, Vec<Vec<u8>>)> {
+		Err(error::ErrorKind::NotAvailableOnLightClient.into())
 	}
-
-	state
 }
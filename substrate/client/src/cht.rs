@@ -0,0 +1,180 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Canonical Hash Trie (CHT) support.
+//!
+//! A CHT lets a light client, which holds only a handful of trusted roots,
+//! verify *any* historical header without storing the full header chain.
+//! The chain is partitioned into fixed-size intervals; for each completed
+//! interval we build a trie mapping the (big-endian) block number to that
+//! block's header hash, and keep only the resulting root. Checking a header
+//! then becomes checking a single-entry Merkle proof against the interval's
+//! stored root.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use hashdb::HashDB;
+use memorydb::MemoryDB;
+use patricia_trie::{TrieDB, TrieDBMut, Trie, TrieMut};
+use primitives::H256;
+use primitives::block::Number as BlockNumber;
+
+use error::{self, ErrorKind};
+
+/// Number of blocks covered by a single CHT.
+pub const SIZE: u64 = 2048;
+
+/// Returns the number of the CHT covering `number`. This does not check
+/// whether that interval has been completed yet.
+pub fn block_to_cht_number(number: BlockNumber) -> u64 {
+	number / SIZE
+}
+
+/// Returns `true` when `number` is the last block of its interval, i.e. the
+/// one import after which the interval's CHT root can be computed.
+pub fn is_final_block_of_cht(number: BlockNumber) -> bool {
+	number != 0 && (number + 1) % SIZE == 0
+}
+
+/// Returns the half-open block number range covered by CHT `cht_num`
+/// (its end, the first block of the next interval, is excluded).
+pub fn cht_range(cht_num: u64) -> ::std::ops::Range<BlockNumber> {
+	(cht_num * SIZE)..((cht_num + 1) * SIZE)
+}
+
+fn encode_cht_key(number: BlockNumber) -> [u8; 8] {
+	let mut key = [0u8; 8];
+	key[0] = (number >> 56) as u8;
+	key[1] = (number >> 48) as u8;
+	key[2] = (number >> 40) as u8;
+	key[3] = (number >> 32) as u8;
+	key[4] = (number >> 24) as u8;
+	key[5] = (number >> 16) as u8;
+	key[6] = (number >> 8) as u8;
+	key[7] = number as u8;
+	key
+}
+
+/// Build the CHT root for a completed interval out of its `(number, hash)` pairs.
+///
+/// `header_hashes` must cover exactly `cht_range(cht_num)`, in any order.
+pub fn build_cht_root<I: IntoIterator<Item = (BlockNumber, H256)>>(header_hashes: I) -> H256 {
+	let mut db = MemoryDB::new();
+	let mut root = H256::default();
+	{
+		let mut trie = TrieDBMut::new(&mut db, &mut root);
+		for (number, hash) in header_hashes {
+			trie.insert(&encode_cht_key(number), hash.as_bytes());
+		}
+	}
+	root
+}
+
+/// Build a Merkle proof that `(number -> hash)` is an entry of the CHT rooted at `cht_root`.
+///
+/// Returns the raw trie nodes along the path to `number`'s entry.
+pub fn build_cht_proof<I: IntoIterator<Item = (BlockNumber, H256)>>(
+	number: BlockNumber,
+	header_hashes: I,
+) -> error::Result<Vec<Vec<u8>>> {
+	let mut db = MemoryDB::new();
+	let mut root = H256::default();
+	{
+		let mut trie = TrieDBMut::new(&mut db, &mut root);
+		for (n, hash) in header_hashes {
+			trie.insert(&encode_cht_key(n), hash.as_bytes());
+		}
+	}
+
+	let recorded = RefCell::new(HashMap::new());
+	{
+		let proxy = RecordingHashDB { db: &db, recorded: &recorded };
+		let trie = TrieDB::new(&proxy, &root).map_err(|_| ErrorKind::InvalidCHTProof)?;
+		trie.get(&encode_cht_key(number)).map_err(|_| ErrorKind::InvalidCHTProof)?;
+	}
+
+	Ok(recorded.into_inner().into_iter().map(|(_, node)| node).collect())
+}
+
+/// `HashDB` adapter that forwards lookups to `db` while copying every node it
+/// is asked for into `recorded`; used to capture the minimal set of nodes a
+/// single-key lookup walks through.
+struct RecordingHashDB<'a> {
+	db: &'a HashDB,
+	recorded: &'a RefCell<HashMap<H256, Vec<u8>>>,
+}
+
+impl<'a> HashDB for RecordingHashDB<'a> {
+	fn get(&self, hash: &H256) -> Option<Vec<u8>> {
+		let node = self.db.get(hash)?;
+		self.recorded.borrow_mut().entry(*hash).or_insert_with(|| node.clone());
+		Some(node)
+	}
+
+	fn contains(&self, hash: &H256) -> bool {
+		self.db.contains(hash)
+	}
+}
+
+/// Check that `(number -> hash)` is an entry of the CHT rooted at `cht_root`, using `proof`
+/// as the set of trie nodes along the path to that entry.
+///
+/// Fails with `InvalidCHTProof` if `proof` doesn't contain every node the lookup needs, or
+/// if the entry it finds doesn't match `hash`.
+pub fn check_cht_proof(cht_root: H256, number: BlockNumber, hash: H256, proof: Vec<Vec<u8>>) -> error::Result<()> {
+	let mut db = MemoryDB::new();
+	for node in proof {
+		db.insert(&node);
+	}
+
+	let trie = TrieDB::new(&db, &cht_root).map_err(|_| ErrorKind::InvalidCHTProof)?;
+	let value = trie.get(&encode_cht_key(number)).map_err(|_| ErrorKind::InvalidCHTProof)?;
+	match value {
+		Some(ref value) if &value[..] == hash.as_bytes() => Ok(()),
+		_ => Err(ErrorKind::InvalidCHTProof.into()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn header_hashes() -> Vec<(BlockNumber, H256)> {
+		cht_range(0).map(|number| (number, H256::default())).collect()
+	}
+
+	#[test]
+	fn build_and_check_cht_proof_roundtrip() {
+		let number = SIZE / 2;
+		let root = build_cht_root(header_hashes());
+		let proof = build_cht_proof(number, header_hashes()).unwrap();
+
+		assert!(check_cht_proof(root, number, H256::default(), proof).is_ok());
+	}
+
+	#[test]
+	fn check_cht_proof_rejects_a_tampered_proof() {
+		let number = SIZE / 2;
+		let root = build_cht_root(header_hashes());
+		let mut proof = build_cht_proof(number, header_hashes()).unwrap();
+
+		// Corrupt the first proof node so it no longer decodes into a valid trie node.
+		let first = proof.get_mut(0).expect("a 2048-entry trie's proof always has nodes");
+		first.push(0xff);
+
+		assert!(check_cht_proof(root, number, H256::default(), proof).is_err());
+	}
+}
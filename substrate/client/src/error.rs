@@ -81,6 +81,32 @@ error_chain! {
 			description("bad justification for header"),
 			display("bad justification for header: {}", h),
 		}
+
+		/// Remote execution proof does not match the claimed result, or dereferences
+		/// a trie node the proof doesn't contain.
+		InvalidExecutionProof {
+			description("invalid execution proof"),
+			display("Remote node has responded with invalid execution proof"),
+		}
+
+		/// The requested operation needs state that is not locally available (e.g. on a
+		/// light client), or its result cannot be proven to a node that lacks that state.
+		NotAvailableOnLightClient {
+			description("not available on light client"),
+			display("This method is not available when the requested state is not locally available"),
+		}
+
+		/// A Canonical Hash Trie proof failed to check out against its claimed root.
+		InvalidCHTProof {
+			description("invalid CHT proof"),
+			display("Remote node has responded with invalid header proof"),
+		}
+
+		/// An on-demand request exhausted its retries without a peer answering it.
+		RemoteFetchFailed {
+			description("remote fetch failed"),
+			display("Remote data fetch has failed"),
+		}
 	}
 }
 
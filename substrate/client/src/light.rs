@@ -0,0 +1,120 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Light client support: on-demand requests for data a light client doesn't hold
+//! locally, together with the proofs needed to trust the answers.
+//!
+//! `Fetcher` models every such request as one variant of `RemoteRequest`; adding a
+//! new light-client capability means adding a variant plus a verifier (see `cht` and
+//! `state_machine::proving_backend` for the two that exist today), not a new bespoke
+//! fetch path.
+
+use futures::Future;
+use futures::sync::oneshot;
+
+use primitives::H256;
+use primitives::block::{Header, Number as BlockNumber};
+
+use cht;
+use error;
+
+/// A response to a remote request: resolves once the request either succeeds or
+/// exhausts its retry policy.
+pub type RemoteResponse<T> = oneshot::Receiver<error::Result<T>>;
+
+/// Parameters of a remote call, i.e. a `RemoteCallExecutor::call`.
+#[derive(Debug, Clone)]
+pub struct RemoteCallRequest {
+	/// Block the call is made against.
+	pub block: H256,
+	/// Runtime method to call.
+	pub method: String,
+	/// Call arguments.
+	pub call_data: Vec<u8>,
+}
+
+/// Parameters of a remote header request, answered with `{ header, cht_proof }`.
+#[derive(Debug, Clone)]
+pub struct RemoteHeaderRequest {
+	/// Number of the CHT interval the requested block falls into.
+	pub cht_number: u64,
+	/// Number of the requested block.
+	pub block: BlockNumber,
+}
+
+/// Parameters of a remote storage read.
+#[derive(Debug, Clone)]
+pub struct RemoteStorageRequest {
+	/// Block the read is made against.
+	pub block: H256,
+	/// Storage key to read.
+	pub key: Vec<u8>,
+}
+
+/// Parameters of a remote CHT root request.
+#[derive(Debug, Clone)]
+pub struct RemoteCHTRequest {
+	/// Number of the CHT interval whose root is requested.
+	pub cht_number: u64,
+}
+
+/// Something that can answer requests a light client can't satisfy out of local data.
+///
+/// Every method just enqueues the request and hands back a future for its eventual
+/// answer; how requests are actually dispatched to peers, retried and timed out is
+/// the default `OnDemand` dispatcher's job, not the caller's.
+pub trait Fetcher: Send + Sync {
+	/// Fetch the result of executing `method(call_data)` at a block, together with the
+	/// execution proof a `RemoteCallExecutor` needs to check it against the block's
+	/// `state_root`.
+	fn remote_call(&self, request: RemoteCallRequest) -> RemoteResponse<(Vec<u8>, Vec<Vec<u8>>)>;
+	/// Fetch a historical header, together with a CHT proof tying it to the interval
+	/// root the caller already trusts.
+	fn remote_header(&self, request: RemoteHeaderRequest) -> RemoteResponse<(Header, Vec<Vec<u8>>)>;
+	/// Fetch a single storage value, together with a state trie proof of its presence
+	/// (or absence) at the block's `state_root`.
+	fn remote_storage(&self, request: RemoteStorageRequest) -> RemoteResponse<(Option<Vec<u8>>, Vec<Vec<u8>>)>;
+	/// Fetch the committed root of a CHT interval.
+	fn remote_cht_root(&self, request: RemoteCHTRequest) -> RemoteResponse<H256>;
+}
+
+/// Fetch a historical header and check it against the CHT root the caller already
+/// trusts, so a light client never has to take a remote peer's word for a header.
+///
+/// This is the only sanctioned way to turn a `RemoteHeaderRequest` into a `Header`:
+/// `fetcher.remote_header` alone just hands back whatever a peer answered with.
+pub fn check_remote_header(fetcher: &Fetcher, cht_root: H256, request: RemoteHeaderRequest) -> error::Result<Header> {
+	let (header, cht_proof) = fetcher.remote_header(request.clone()).wait()
+		.map_err(|_| error::Error::from(error::ErrorKind::RemoteFetchFailed))??;
+	cht::check_cht_proof(cht_root, request.block, header.hash(), cht_proof)?;
+	Ok(header)
+}
+
+/// One outstanding request, in the shape the dispatcher queues and retries it.
+///
+/// This is the enum the on-demand layer actually round-robins across peers; the
+/// per-kind request structs above are what callers build, the response channel is
+/// attached once the request is queued.
+pub enum RemoteRequest {
+	/// See `RemoteCallRequest`.
+	Call(RemoteCallRequest, oneshot::Sender<error::Result<(Vec<u8>, Vec<Vec<u8>>)>>),
+	/// See `RemoteHeaderRequest`.
+	Header(RemoteHeaderRequest, oneshot::Sender<error::Result<(Header, Vec<Vec<u8>>)>>),
+	/// See `RemoteStorageRequest`.
+	Storage(RemoteStorageRequest, oneshot::Sender<error::Result<(Option<Vec<u8>>, Vec<Vec<u8>>)>>),
+	/// See `RemoteCHTRequest`.
+	CHT(RemoteCHTRequest, oneshot::Sender<error::Result<H256>>),
+}
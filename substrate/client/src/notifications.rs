@@ -0,0 +1,121 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Storage change notifications.
+//!
+//! `Backend::commit_operation` is the single chokepoint where state mutations land;
+//! this registry lets other parts of the node (chiefly the RPC layer's
+//! `state_subscribeStorage`) observe them without polling. Subscribers register a key
+//! filter and get woken, per committed block, only with the subset of that block's
+//! changes they actually asked for.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use futures::sync::mpsc;
+
+use primitives::H256;
+
+/// A single storage change: the key, and its new value (`None` for a deletion).
+pub type StorageChange = (Vec<u8>, Option<Vec<u8>>);
+
+/// The changes a committed block made to storage, filtered to what a subscriber asked for.
+pub type StorageChangeSet = Vec<StorageChange>;
+
+/// What a subscriber wants to hear about.
+pub enum Filter {
+	/// Every change, regardless of key.
+	All,
+	/// Only changes to these keys, or to keys having one of these as a prefix.
+	Keys(Vec<Vec<u8>>),
+}
+
+impl Filter {
+	fn matches(&self, key: &[u8]) -> bool {
+		match *self {
+			Filter::All => true,
+			Filter::Keys(ref keys) => keys.iter().any(|interesting| key.starts_with(&interesting[..])),
+		}
+	}
+}
+
+/// Identifies a subscription, so it can later be cancelled.
+pub type SubscriberId = u64;
+
+struct Subscriber {
+	filter: Filter,
+	sink: mpsc::UnboundedSender<(H256, StorageChangeSet)>,
+}
+
+struct Inner {
+	next_id: SubscriberId,
+	subscribers: HashMap<SubscriberId, Subscriber>,
+}
+
+/// Registry of storage-change subscribers, fed by `Backend::commit_operation`.
+pub struct StorageNotifications {
+	inner: Mutex<Inner>,
+}
+
+impl StorageNotifications {
+	/// Create an empty registry.
+	pub fn new() -> Self {
+		StorageNotifications {
+			inner: Mutex::new(Inner { next_id: 0, subscribers: HashMap::new() }),
+		}
+	}
+
+	/// Subscribe to storage changes matching `filter`.
+	///
+	/// Returns the subscription's id, to cancel it later with `unsubscribe`, and the
+	/// receiving half of its channel.
+	pub fn subscribe(&self, filter: Filter) -> (SubscriberId, mpsc::UnboundedReceiver<(H256, StorageChangeSet)>) {
+		let (sink, stream) = mpsc::unbounded();
+		let mut inner = self.lock();
+		let id = inner.next_id;
+		inner.next_id += 1;
+		inner.subscribers.insert(id, Subscriber { filter, sink });
+		(id, stream)
+	}
+
+	/// Cancel a subscription. No-op if it was already dropped or cancelled.
+	pub fn unsubscribe(&self, id: SubscriberId) {
+		self.lock().subscribers.remove(&id);
+	}
+
+	/// Called by `Backend::commit_operation` on every successful commit, with the
+	/// block hash and the full set of storage changes it just applied (already
+	/// available from `BlockImportOperation::set_storage`, so no extra diffing is
+	/// needed here). Each subscriber only wakes for the subset of `changes` it
+	/// is interested in, and not at all if that subset is empty.
+	pub fn trigger(&self, block: H256, changes: &[StorageChange]) {
+		let inner = self.lock();
+		for subscriber in inner.subscribers.values() {
+			let filtered: StorageChangeSet = changes.iter()
+				.filter(|&&(ref key, _)| subscriber.filter.matches(key))
+				.cloned()
+				.collect();
+			if !filtered.is_empty() {
+				// An unbounded send only fails once every receiver has been dropped;
+				// the subscriber will be reaped the next time someone unsubscribes it.
+				let _ = subscriber.sink.unbounded_send((block, filtered));
+			}
+		}
+	}
+
+	fn lock(&self) -> ::std::sync::MutexGuard<Inner> {
+		self.inner.lock().expect("only panics if a previous holder panicked")
+	}
+}
@@ -0,0 +1,301 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Default `Fetcher`: queues `RemoteRequest`s, round-robins them across connected
+//! peers, and re-queues them on peer disconnect or timeout until they've either been
+//! answered or exhausted their retries.
+//!
+//! The networking layer is the only thing that knows how to actually talk to a peer;
+//! it drives this dispatcher through `next_dispatch`/`on_response`/`on_timeout` and
+//! `on_connect`/`on_disconnect` rather than `OnDemand` reaching into the network itself.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use futures::sync::oneshot;
+
+use cht;
+use error;
+use light::{
+	Fetcher, RemoteRequest,
+	RemoteCallRequest, RemoteHeaderRequest, RemoteStorageRequest, RemoteCHTRequest,
+	RemoteResponse,
+};
+use primitives::H256;
+use primitives::block::Header;
+
+/// Peer identifier, as seen by the dispatcher. Kept as a plain alias, rather than
+/// depending on the network crate's peer type, so this module stays usable from tests
+/// and from networking backends alike.
+pub type PeerId = usize;
+
+/// A request is sent at most this many times, its first attempt included, before its
+/// caller's future resolves to an error. Each resend goes to the next peer in
+/// round-robin order, which may be a peer that already failed it.
+pub const MAX_RETRY_COUNT: usize = 3;
+
+type RequestId = u64;
+
+/// A queued request, waiting for a peer to send it to.
+struct Pending {
+	request: RemoteRequest,
+	attempts: usize,
+}
+
+struct OnDemandCore {
+	next_id: RequestId,
+	queue: VecDeque<RequestId>,
+	pending: HashMap<RequestId, Pending>,
+	/// Requests currently dispatched to a peer, so a timeout or disconnect knows what
+	/// to requeue.
+	in_flight: HashMap<RequestId, PeerId>,
+	peers: Vec<PeerId>,
+	next_peer: usize,
+}
+
+/// Default on-demand request dispatcher. One instance is shared between the `Fetcher`
+/// consumers (e.g. `RemoteCallExecutor`) and the networking code that actually talks to
+/// peers on its behalf.
+pub struct OnDemand {
+	core: Mutex<OnDemandCore>,
+}
+
+impl OnDemand {
+	/// Create a new, peerless on-demand dispatcher.
+	pub fn new() -> Self {
+		OnDemand {
+			core: Mutex::new(OnDemandCore {
+				next_id: 0,
+				queue: VecDeque::new(),
+				pending: HashMap::new(),
+				in_flight: HashMap::new(),
+				peers: Vec::new(),
+				next_peer: 0,
+			}),
+		}
+	}
+
+	/// Register a newly connected peer as a candidate to serve requests.
+	pub fn on_connect(&self, peer: PeerId) {
+		let mut core = self.lock();
+		core.peers.push(peer);
+	}
+
+	/// Drop a disconnected peer, re-queueing anything it had in flight.
+	pub fn on_disconnect(&self, peer: PeerId) {
+		let mut core = self.lock();
+		core.peers.retain(|p| *p != peer);
+		if core.next_peer >= core.peers.len() {
+			core.next_peer = 0;
+		}
+
+		let stuck: Vec<RequestId> = core.in_flight.iter()
+			.filter(|&(_, &assigned)| assigned == peer)
+			.map(|(&id, _)| id)
+			.collect();
+		for id in stuck {
+			core.in_flight.remove(&id);
+			self.requeue_or_fail(&mut core, id);
+		}
+	}
+
+	/// Called by the networking layer once it has a free dispatch slot: pops the next
+	/// pending request, round-robins a connected peer to send it to, and remembers the
+	/// pairing so a later timeout or disconnect can find it again.
+	pub fn next_dispatch(&self) -> Option<(PeerId, RequestId, RemoteRequestParams)> {
+		let mut core = self.lock();
+		if core.peers.is_empty() {
+			return None;
+		}
+
+		let id = core.queue.pop_front()?;
+		let peer = core.peers[core.next_peer % core.peers.len()];
+		core.next_peer = (core.next_peer + 1) % core.peers.len();
+		core.in_flight.insert(id, peer);
+
+		let params = core.pending.get(&id).map(|p| RemoteRequestParams::from(&p.request))
+			.expect("id just popped from queue always has a matching pending entry");
+		Some((peer, id, params))
+	}
+
+	/// Called by the networking layer when a peer answers `id`'s request. `result`
+	/// should already be the outcome of verifying the response against whatever root
+	/// the caller trusts (a CHT root, a `state_root`, ...); `Err` here re-queues the
+	/// request for the next round-robin peer rather than failing it outright, since an
+	/// invalid proof is the peer's fault, not a reason to give up. An answer for a
+	/// request that is not in flight (e.g. one already timed out) is ignored.
+	pub fn on_response(&self, id: RequestId, result: Result<RemoteResponseValue, error::Error>) {
+		let mut core = self.lock();
+		if core.in_flight.remove(&id).is_none() {
+			return;
+		}
+		match result {
+			Ok(value) => {
+				if let Some(pending) = core.pending.remove(&id) {
+					resolve(pending.request, Ok(value));
+				}
+			},
+			Err(_) => self.requeue_or_fail(&mut core, id),
+		}
+	}
+
+	/// Called by the networking layer when a peer doesn't answer `id`'s request in
+	/// time: re-queues it for the next round-robin peer unless it has already exhausted
+	/// its retries, in which case the caller's future resolves to an error. Ignored for
+	/// a request that is not in flight.
+	pub fn on_timeout(&self, id: RequestId) {
+		let mut core = self.lock();
+		if core.in_flight.remove(&id).is_none() {
+			return;
+		}
+		self.requeue_or_fail(&mut core, id);
+	}
+
+	fn requeue_or_fail(&self, core: &mut OnDemandCore, id: RequestId) {
+		let give_up = {
+			let pending = match core.pending.get_mut(&id) {
+				Some(pending) => pending,
+				None => return,
+			};
+			pending.attempts += 1;
+			pending.attempts >= MAX_RETRY_COUNT
+		};
+
+		if give_up {
+			if let Some(pending) = core.pending.remove(&id) {
+				resolve(pending.request, Err(error::ErrorKind::RemoteFetchFailed.into()));
+			}
+		} else {
+			core.queue.push_back(id);
+		}
+	}
+
+	fn enqueue(&self, request: RemoteRequest) {
+		let mut core = self.lock();
+		let id = core.next_id;
+		core.next_id += 1;
+		core.queue.push_back(id);
+		core.pending.insert(id, Pending { request, attempts: 0 });
+	}
+
+	fn lock(&self) -> ::std::sync::MutexGuard<OnDemandCore> {
+		self.core.lock().expect("only panics if a previous holder panicked")
+	}
+}
+
+/// The parameters of a dispatched request, without the response channel - this is what
+/// actually goes out over the wire.
+pub enum RemoteRequestParams {
+	/// See `RemoteCallRequest`.
+	Call(RemoteCallRequest),
+	/// See `RemoteHeaderRequest`.
+	Header(RemoteHeaderRequest),
+	/// See `RemoteStorageRequest`.
+	Storage(RemoteStorageRequest),
+	/// See `RemoteCHTRequest`.
+	CHT(RemoteCHTRequest),
+}
+
+impl<'a> From<&'a RemoteRequest> for RemoteRequestParams {
+	fn from(request: &'a RemoteRequest) -> Self {
+		match *request {
+			RemoteRequest::Call(ref req, _) => RemoteRequestParams::Call(req.clone()),
+			RemoteRequest::Header(ref req, _) => RemoteRequestParams::Header(req.clone()),
+			RemoteRequest::Storage(ref req, _) => RemoteRequestParams::Storage(req.clone()),
+			RemoteRequest::CHT(ref req, _) => RemoteRequestParams::CHT(req.clone()),
+		}
+	}
+}
+
+/// The verified value of whichever request kind `on_response` is resolving.
+pub enum RemoteResponseValue {
+	/// See `Fetcher::remote_call`.
+	Call(Vec<u8>, Vec<Vec<u8>>),
+	/// See `Fetcher::remote_header`.
+	Header(::primitives::block::Header, Vec<Vec<u8>>),
+	/// See `Fetcher::remote_storage`.
+	Storage(Option<Vec<u8>>, Vec<Vec<u8>>),
+	/// See `Fetcher::remote_cht_root`.
+	CHT(::primitives::H256),
+}
+
+/// Check a peer's raw answer to a `RemoteHeaderRequest` against the CHT root the
+/// caller already trusts, producing the verified value `on_response` expects.
+///
+/// This is the piece `on_response`'s doc comment refers to as "already the outcome
+/// of verifying the response": whatever drives the network (there's no networking
+/// crate in this tree to call it for us) must route a peer's `(header, cht_proof)`
+/// answer through this - or a rejection - before ever calling `on_response`, the
+/// on-demand analogue of `light::check_remote_header` for callers going through the
+/// queue/dispatch path instead of calling a `Fetcher` directly.
+pub fn verify_header_response(
+	cht_root: H256,
+	request: &RemoteHeaderRequest,
+	header: Header,
+	cht_proof: Vec<Vec<u8>>,
+) -> Result<RemoteResponseValue, error::Error> {
+	cht::check_cht_proof(cht_root, request.block, header.hash(), cht_proof.clone())?;
+	Ok(RemoteResponseValue::Header(header, cht_proof))
+}
+
+fn resolve(request: RemoteRequest, result: Result<RemoteResponseValue, error::Error>) {
+	match (request, result) {
+		(RemoteRequest::Call(_, sender), Ok(RemoteResponseValue::Call(data, proof))) => {
+			let _ = sender.send(Ok((data, proof)));
+		},
+		(RemoteRequest::Header(_, sender), Ok(RemoteResponseValue::Header(header, proof))) => {
+			let _ = sender.send(Ok((header, proof)));
+		},
+		(RemoteRequest::Storage(_, sender), Ok(RemoteResponseValue::Storage(value, proof))) => {
+			let _ = sender.send(Ok((value, proof)));
+		},
+		(RemoteRequest::CHT(_, sender), Ok(RemoteResponseValue::CHT(root))) => {
+			let _ = sender.send(Ok(root));
+		},
+		(RemoteRequest::Call(_, sender), Err(err)) => { let _ = sender.send(Err(err)); },
+		(RemoteRequest::Header(_, sender), Err(err)) => { let _ = sender.send(Err(err)); },
+		(RemoteRequest::Storage(_, sender), Err(err)) => { let _ = sender.send(Err(err)); },
+		(RemoteRequest::CHT(_, sender), Err(err)) => { let _ = sender.send(Err(err)); },
+		// A response of the wrong kind for its request is a bug in the caller wiring
+		// `on_response` up, not something a peer can trigger; drop it rather than panic.
+		_ => {},
+	}
+}
+
+impl Fetcher for OnDemand {
+	fn remote_call(&self, request: RemoteCallRequest) -> RemoteResponse<(Vec<u8>, Vec<Vec<u8>>)> {
+		let (sender, receiver) = oneshot::channel();
+		self.enqueue(RemoteRequest::Call(request, sender));
+		receiver
+	}
+
+	fn remote_header(&self, request: RemoteHeaderRequest) -> RemoteResponse<(::primitives::block::Header, Vec<Vec<u8>>)> {
+		let (sender, receiver) = oneshot::channel();
+		self.enqueue(RemoteRequest::Header(request, sender));
+		receiver
+	}
+
+	fn remote_storage(&self, request: RemoteStorageRequest) -> RemoteResponse<(Option<Vec<u8>>, Vec<Vec<u8>>)> {
+		let (sender, receiver) = oneshot::channel();
+		self.enqueue(RemoteRequest::Storage(request, sender));
+		receiver
+	}
+
+	fn remote_cht_root(&self, request: RemoteCHTRequest) -> RemoteResponse<::primitives::H256> {
+		let (sender, receiver) = oneshot::channel();
+		self.enqueue(RemoteRequest::CHT(request, sender));
+		receiver
+	}
+}
@@ -17,11 +17,49 @@
 //! Conrete externalities implementation.
 
 use std::{error, fmt};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use triehash::trie_root;
 use backend::Backend;
 use {Externalities, OverlayedChanges};
 
+/// Every child storage entry is namespaced under the parent key space by prefixing it
+/// with this marker plus its owning `storage_key`, length-prefixed so two different
+/// `(storage_key, key)` pairs can never encode to the same overlay/backend key.
+const CHILD_STORAGE_KEY_PREFIX: &'static [u8] = b":child_storage:default:";
+
+/// Build the namespaced key a child entry is actually stored under.
+fn child_key(storage_key: &[u8], key: &[u8]) -> Vec<u8> {
+	let mut full_key = child_key_prefix(storage_key);
+	full_key.extend_from_slice(key);
+	full_key
+}
+
+/// The namespaced prefix shared by every entry of the child trie at `storage_key`; this
+/// is also the key its folded root is stored under in the top-level trie.
+fn child_key_prefix(storage_key: &[u8]) -> Vec<u8> {
+	let mut prefix = Vec::with_capacity(CHILD_STORAGE_KEY_PREFIX.len() + 4 + storage_key.len());
+	prefix.extend_from_slice(CHILD_STORAGE_KEY_PREFIX);
+	prefix.push((storage_key.len() >> 24) as u8);
+	prefix.push((storage_key.len() >> 16) as u8);
+	prefix.push((storage_key.len() >> 8) as u8);
+	prefix.push(storage_key.len() as u8);
+	prefix.extend_from_slice(storage_key);
+	prefix
+}
+
+/// If `key` is a namespaced child entry, returns the `storage_key` that owns it.
+fn owning_storage_key(key: &[u8]) -> Option<Vec<u8>> {
+	if !key.starts_with(CHILD_STORAGE_KEY_PREFIX) {
+		return None;
+	}
+	let rest = &key[CHILD_STORAGE_KEY_PREFIX.len()..];
+	if rest.len() < 4 {
+		return None;
+	}
+	let len = ((rest[0] as usize) << 24) | ((rest[1] as usize) << 16) | ((rest[2] as usize) << 8) | (rest[3] as usize);
+	rest.get(4..4 + len).map(|storage_key| storage_key.to_vec())
+}
+
 /// Errors that can occur when interacting with the externalities.
 #[derive(Debug, Copy, Clone)]
 pub enum Error<B, E> {
@@ -85,17 +123,107 @@ impl<'a, B: 'a> Externalities for Ext<'a, B>
 		self.overlay.set_storage(key, value);
 	}
 
+	fn child_storage(&self, storage_key: &[u8], key: &[u8]) -> Option<Vec<u8>> {
+		self.storage(&child_key(storage_key, key))
+	}
+
+	fn place_child_storage(&mut self, storage_key: &[u8], key: Vec<u8>, value: Option<Vec<u8>>) {
+		self.place_storage(child_key(storage_key, &key), value)
+	}
+
+	fn kill_child_storage(&mut self, storage_key: &[u8]) {
+		let prefix = child_key_prefix(storage_key);
+		for (key, _) in self.all_pairs_under(&prefix) {
+			self.overlay.set_storage(key, None);
+		}
+	}
+
+	fn child_storage_root(&self, storage_key: &[u8]) -> [u8; 32] {
+		let prefix = child_key_prefix(storage_key);
+		trie_root(self.all_pairs_under(&prefix).into_iter()
+			.map(|(k, v)| (k[prefix.len()..].to_vec(), v))).0
+	}
+
 	fn chain_id(&self) -> u64 {
 		42
 	}
 
 	fn storage_root(&self) -> [u8; 32] {
-		trie_root(self.backend.pairs().into_iter()
+		let mut top: HashMap<Vec<u8>, Option<Vec<u8>>> = self.backend.pairs().into_iter()
+			.map(|(k, v)| (k, Some(v)))
+			.chain(self.overlay.committed.clone().into_iter())
+			.chain(self.overlay.prospective.clone().into_iter())
+			.collect();
+
+		// Child tries are never hashed in directly; each contributes a single entry,
+		// keyed by its own namespaced prefix, holding its independently-computed root.
+		// Only entries that are still live (`Some`) own a child trie - a child key
+		// written then deleted within this overlay must not fold a stale, empty
+		// child root into the top-level trie.
+		let child_storage_keys: HashSet<Vec<u8>> = top.iter()
+			.filter(|&(_, value)| value.is_some())
+			.filter_map(|(key, _)| owning_storage_key(key))
+			.collect();
+		top.retain(|key, _| owning_storage_key(key).is_none());
+		for storage_key in child_storage_keys {
+			let root = self.child_storage_root(&storage_key);
+			top.insert(child_key_prefix(&storage_key), Some(root.to_vec()));
+		}
+
+		trie_root(top.into_iter().filter_map(|(k, maybe_val)| maybe_val.map(|val| (k, val)))).0
+	}
+}
+
+impl<'a, B: 'a> Ext<'a, B>
+	where B: Backend
+{
+	/// All live `(key, value)` pairs - backend plus overlay, prospective shadowing
+	/// committed shadowing the backend - whose key starts with `prefix`.
+	fn all_pairs_under(&self, prefix: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+		self.backend.pairs().into_iter()
 			.map(|(k, v)| (k, Some(v)))
 			.chain(self.overlay.committed.clone().into_iter())
 			.chain(self.overlay.prospective.clone().into_iter())
 			.collect::<HashMap<_, _>>()
 			.into_iter()
-			.filter_map(|(k, maybe_val)| maybe_val.map(|val| (k, val)))).0
+			.filter(|&(ref k, _)| k.starts_with(prefix))
+			.filter_map(|(k, maybe_val)| maybe_val.map(|val| (k, val)))
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	struct NoopBackend;
+
+	impl Backend for NoopBackend {
+		type Error = String;
+
+		fn storage(&self, _key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+			Ok(None)
+		}
+
+		fn pairs(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+			Vec::new()
+		}
+	}
+
+	#[test]
+	fn storage_root_ignores_a_child_key_written_then_deleted_in_the_same_overlay() {
+		let backend = NoopBackend;
+
+		let mut overlay = OverlayedChanges::default();
+		let mut ext = Ext { overlay: &mut overlay, backend: &backend };
+		ext.place_child_storage(b"child_trie", b"some_key".to_vec(), Some(b"some_value".to_vec()));
+		ext.place_child_storage(b"child_trie", b"some_key".to_vec(), None);
+		let root_with_tombstoned_child = ext.storage_root();
+
+		let mut empty_overlay = OverlayedChanges::default();
+		let empty_ext = Ext { overlay: &mut empty_overlay, backend: &backend };
+		let empty_root = empty_ext.storage_root();
+
+		assert_eq!(root_with_tombstoned_child, empty_root);
 	}
 }
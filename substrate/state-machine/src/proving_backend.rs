@@ -0,0 +1,260 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Trie-based state backend which records every trie node it reads, so the
+//! recorded set can later be shipped as a compact execution proof, plus the
+//! matching verifier-side backend that checks a call against such a proof.
+
+use std::cell::Cell;
+use hashdb::HashDB;
+use memorydb::MemoryDB;
+use patricia_trie::{TrieDB, Trie};
+use primitives::H256;
+use backend::Backend;
+use recording_ext::ProofRecorder;
+use {OverlayedChanges, CodeExecutor, execute};
+
+/// A `Backend` whose storage is a Merkle patricia trie, and which can hand
+/// out the raw `(root, node-db)` pair backing it.
+///
+/// `ProvingBackend` is built on top of this rather than the plain `Backend`
+/// trait because recording individual trie nodes only makes sense for
+/// backends that are actually trie-shaped.
+pub trait TrieBackend: Backend {
+	/// The current root hash of the trie.
+	fn root(&self) -> H256;
+	/// The node database backing the trie.
+	fn db(&self) -> &HashDB;
+}
+
+/// Wraps a local, trie-backed `TrieBackend` and records every trie node read
+/// while servicing `storage` lookups made during execution.
+///
+/// The recorded `(hash -> encoded node)` pairs are exactly the proof a
+/// verifier needs to replay the same lookups starting from the `state_root`
+/// alone, without holding the rest of the state.
+pub struct ProvingBackend<'a, S: 'a> {
+	backend: &'a S,
+	recorded: ProofRecorder,
+	pairs_enumerated: Cell<bool>,
+}
+
+impl<'a, S: 'a + TrieBackend> ProvingBackend<'a, S> {
+	/// Create a new proving backend wrapping the given trie backend.
+	pub fn new(backend: &'a S) -> Self {
+		ProvingBackend {
+			backend,
+			recorded: ProofRecorder::new(),
+			pairs_enumerated: Cell::new(false),
+		}
+	}
+
+	/// Consume the backend, returning the recorded proof nodes in the order they were
+	/// first read, so a full node's `execution_proof` returns the same bytes for the
+	/// same call every time.
+	pub fn extract_proof(self) -> Vec<Vec<u8>> {
+		self.recorded.into_proof()
+	}
+
+	/// Whether the wrapped call enumerated the full state (via `pairs()`), e.g. by
+	/// calling `storage_root()`/`child_storage_root()`. `pairs()` can't record a
+	/// trie-sized proof of "every key", so a `true` here means `extract_proof`'s
+	/// result cannot be trusted to reproduce the call: the caller should reject it
+	/// rather than ship a proof that silently omits most of the state.
+	pub fn enumerated_full_state(&self) -> bool {
+		self.pairs_enumerated.get()
+	}
+}
+
+impl<'a, S: 'a + TrieBackend> Backend for ProvingBackend<'a, S> {
+	type Error = String;
+
+	fn storage(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+		let root = self.backend.root();
+		let proxy = RecordingHashDB { db: self.backend.db(), recorded: &self.recorded };
+		trie_get(&proxy, &root, key)
+	}
+
+	fn pairs(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+		// Proving backends only ever service the individual reads an execution makes;
+		// dumping the full state here would defeat the purpose of a trie-sized proof.
+		// Flag the attempt rather than quietly handing back an empty (and therefore
+		// wrong) result, so `enumerated_full_state` lets the caller reject the proof.
+		self.pairs_enumerated.set(true);
+		Vec::new()
+	}
+}
+
+/// `HashDB` adapter that forwards lookups to the wrapped trie storage while
+/// copying every node it is asked for into `recorded`.
+struct RecordingHashDB<'a> {
+	db: &'a HashDB,
+	recorded: &'a ProofRecorder,
+}
+
+impl<'a> HashDB for RecordingHashDB<'a> {
+	fn get(&self, hash: &H256) -> Option<Vec<u8>> {
+		let node = self.db.get(hash)?;
+		self.recorded.record(*hash, node.clone());
+		Some(node)
+	}
+
+	fn contains(&self, hash: &H256) -> bool {
+		self.db.contains(hash)
+	}
+}
+
+fn trie_get<D: HashDB>(db: &D, root: &H256, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+	let trie = TrieDB::new(db, root).map_err(|e| format!("Trie lookup error: {:?}", e))?;
+	trie.get(key)
+		.map(|value| value.map(|value| value.to_vec()))
+		.map_err(|e| format!("Trie lookup error: {:?}", e))
+}
+
+/// Verifier-side backend: an in-memory node set rooted at a `state_root` the
+/// verifier already trusts (e.g. taken from a locally-held header).
+///
+/// Unlike `ProvingBackend`, this never records anything - it only ever
+/// contains the nodes handed to it in the proof. Dereferencing a node hash
+/// that isn't in that set surfaces as a lookup error, which the caller should
+/// treat as an invalid proof rather than a missing key.
+pub struct ProofCheckBackend {
+	root: H256,
+	db: MemoryDB,
+}
+
+impl ProofCheckBackend {
+	/// Build a proof-check backend from the raw nodes of an execution proof.
+	pub fn new(root: H256, proof: Vec<Vec<u8>>) -> Self {
+		let mut db = MemoryDB::new();
+		for node in proof {
+			db.insert(&node);
+		}
+		ProofCheckBackend { root, db }
+	}
+}
+
+impl Backend for ProofCheckBackend {
+	type Error = String;
+
+	fn storage(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+		trie_get(&self.db, &self.root, key)
+	}
+
+	fn pairs(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+		Vec::new()
+	}
+}
+
+/// Replay `method(call_data)` against the nodes of an execution proof, rooted
+/// at `state_root`. Returns the result and the resulting overlayed changes on
+/// success; any node the execution touches that isn't covered by `proof`
+/// surfaces as an error here rather than as a silent wrong answer.
+pub fn check_execution_proof<E: CodeExecutor>(
+	state_root: H256,
+	proof: Vec<Vec<u8>>,
+	executor: &E,
+	method: &str,
+	call_data: &[u8],
+) -> Result<(Vec<u8>, OverlayedChanges), String> {
+	let backend = ProofCheckBackend::new(state_root, proof);
+	let mut changes = OverlayedChanges::default();
+	let return_data = execute(&backend, &mut changes, executor, method, call_data)
+		.map_err(|e| format!("{:?}", e))?;
+	Ok((return_data, changes))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use patricia_trie::TrieDBMut;
+	use patricia_trie::TrieMut;
+
+	struct TestTrieBackend {
+		root: H256,
+		db: MemoryDB,
+	}
+
+	impl Backend for TestTrieBackend {
+		type Error = String;
+
+		fn storage(&self, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+			trie_get(&self.db, &self.root, key)
+		}
+
+		fn pairs(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+			Vec::new()
+		}
+	}
+
+	impl TrieBackend for TestTrieBackend {
+		fn root(&self) -> H256 {
+			self.root
+		}
+
+		fn db(&self) -> &HashDB {
+			&self.db
+		}
+	}
+
+	fn build_test_backend() -> TestTrieBackend {
+		let mut db = MemoryDB::new();
+		let mut root = H256::default();
+		{
+			let mut trie = TrieDBMut::new(&mut db, &mut root);
+			trie.insert(b"key", b"value");
+		}
+		TestTrieBackend { root, db }
+	}
+
+	#[test]
+	fn proving_backend_round_trips_a_storage_read() {
+		let backend = build_test_backend();
+		let state_root = backend.root();
+
+		let proving_backend = ProvingBackend::new(&backend);
+		assert_eq!(proving_backend.storage(b"key"), Ok(Some(b"value".to_vec())));
+		assert!(!proving_backend.enumerated_full_state());
+
+		let proof = proving_backend.extract_proof();
+		let check_backend = ProofCheckBackend::new(state_root, proof);
+		assert_eq!(check_backend.storage(b"key"), Ok(Some(b"value".to_vec())));
+	}
+
+	#[test]
+	fn check_backend_rejects_a_proof_missing_a_touched_node() {
+		let backend = build_test_backend();
+		let state_root = backend.root();
+
+		let proving_backend = ProvingBackend::new(&backend);
+		let _ = proving_backend.storage(b"key");
+		let mut proof = proving_backend.extract_proof();
+		proof.pop();
+
+		let check_backend = ProofCheckBackend::new(state_root, proof);
+		assert!(check_backend.storage(b"key").is_err());
+	}
+
+	#[test]
+	fn pairs_flags_full_state_enumeration_instead_of_returning_empty_silently() {
+		let backend = build_test_backend();
+		let proving_backend = ProvingBackend::new(&backend);
+
+		assert!(!proving_backend.enumerated_full_state());
+		assert_eq!(proving_backend.pairs(), Vec::new());
+		assert!(proving_backend.enumerated_full_state());
+	}
+}
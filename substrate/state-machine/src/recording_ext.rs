@@ -0,0 +1,54 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Ordered proof-node recorder shared by the execution-proof-producing backends.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use primitives::H256;
+
+/// Records `(hash, encoded node)` pairs in first-seen order, deduplicating repeats.
+///
+/// A proof built straight from a `HashMap` is correct but not deterministic -
+/// iterating it twice for the same execution can come out in a different order,
+/// which makes two otherwise-identical calls produce differently-ordered proof
+/// bytes. `ProofRecorder` is the recorder `ProvingBackend` (and any other backend
+/// that wants to expose an `execution_proof`) delegates to, so a full node always
+/// returns the same proof bytes for the same call.
+#[derive(Default)]
+pub struct ProofRecorder {
+	seen: RefCell<HashSet<H256>>,
+	nodes: RefCell<Vec<Vec<u8>>>,
+}
+
+impl ProofRecorder {
+	/// Create an empty recorder.
+	pub fn new() -> Self {
+		ProofRecorder::default()
+	}
+
+	/// Record `node` under `hash`, unless it has already been recorded.
+	pub fn record(&self, hash: H256, node: Vec<u8>) {
+		if self.seen.borrow_mut().insert(hash) {
+			self.nodes.borrow_mut().push(node);
+		}
+	}
+
+	/// Consume the recorder, returning the proof nodes in the order they were first read.
+	pub fn into_proof(self) -> Vec<Vec<u8>> {
+		self.nodes.into_inner()
+	}
+}